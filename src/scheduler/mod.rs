@@ -0,0 +1,186 @@
+// Copyright (c) 2017 Stefan Lankes, RWTH Aachen University
+//
+// MIT License
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+pub mod task;
+#[allow(clippy::module_inception)]
+mod scheduler;
+
+use core::ptr::Shared;
+pub use self::scheduler::Scheduler;
+use self::task::*;
+use synch::spinlock::SpinlockIrqSave;
+
+#[inline(always)]
+fn core_scheduler() -> &'static Scheduler {
+	self::scheduler::this_scheduler()
+}
+
+/// Register the current (booting) task as the idle task of this core. Must
+/// run once on every core, including each application processor as it is
+/// brought up, so that every core gets its own `ready_queue`.
+pub unsafe fn add_idle_task() {
+	self::scheduler::add_idle_task();
+}
+
+/// A handle to a spawned task, which can be used to wait for it to finish
+/// and retrieve its exit value.
+///
+/// Dropping a `JoinHandle` without calling `join` detaches the task: it is
+/// then reclaimed as soon as it finishes, exactly like a task spawned
+/// before `JoinHandle` existed.
+pub struct JoinHandle {
+	tid: TaskId
+}
+
+impl JoinHandle {
+	/// Id of the task this handle belongs to
+	pub fn id(&self) -> TaskId {
+		self.tid
+	}
+
+	/// Block the caller until the task finishes and return its exit value
+	pub fn join(self) -> usize {
+		unsafe { join(self.tid) }
+	}
+}
+
+impl Drop for JoinHandle {
+	fn drop(&mut self) {
+		unsafe { detach(self.tid); }
+	}
+}
+
+/// Spawn a new task and return a handle to it
+pub unsafe fn spawn(func: extern fn(), prio: Priority) -> JoinHandle {
+	let tid = core_scheduler().spawn(func, prio);
+	JoinHandle { tid: tid }
+}
+
+/// Block the caller until task `tid` finishes and return its exit value
+pub unsafe fn join(tid: TaskId) -> usize {
+	core_scheduler().join(tid)
+}
+
+/// Detach task `tid`, so it no longer has to be joined to be reclaimed
+pub unsafe fn detach(tid: TaskId) {
+	core_scheduler().detach(tid)
+}
+
+/// Terminate the current task
+pub unsafe fn do_exit(result: usize) -> ! {
+	core_scheduler().exit(result)
+}
+
+/// Abort the current task
+pub unsafe fn abort() -> ! {
+	core_scheduler().abort()
+}
+
+/// Number of tasks that are currently managed by the scheduler
+pub fn number_of_tasks() -> usize {
+	core_scheduler().number_of_tasks()
+}
+
+/// Block the current task and return a handle to it
+pub unsafe fn block_current_task() -> Shared<Task> {
+	core_scheduler().block_current_task()
+}
+
+/// Block the current task for at most `ticks` timer ticks and return a
+/// handle to it
+pub unsafe fn block_current_task_timeout(ticks: usize) -> Shared<Task> {
+	core_scheduler().block_current_task_timeout(ticks)
+}
+
+/// Whether the current task's last block ended in a timeout
+pub unsafe fn current_task_timed_out() -> bool {
+	core_scheduler().current_task_timed_out()
+}
+
+/// Wakeup a previously blocked task, placing it on whichever core is
+/// currently least loaded
+pub unsafe fn wakeup_task(task: Shared<Task>) {
+	self::scheduler::wakeup_task(task)
+}
+
+/// Called by the timer interrupt handler on every tick
+pub unsafe fn timer_tick() {
+	core_scheduler().timer_tick()
+}
+
+/// Id of the task that is currently running
+pub fn get_current_taskid() -> TaskId {
+	core_scheduler().get_current_taskid()
+}
+
+/// Start addresses of the stacks of the current task
+pub fn get_current_stacks() -> (usize, usize) {
+	core_scheduler().get_current_stacks()
+}
+
+/// Start address of the kernel stack (rsp0) of the current task
+pub fn get_kernel_stack() -> usize {
+	core_scheduler().get_kernel_stack()
+}
+
+/// Priority of the task that is currently running
+pub fn get_current_priority() -> Priority {
+	core_scheduler().get_current_priority()
+}
+
+/// Priority of the task with id `tid`
+pub fn get_priority(tid: TaskId) -> Priority {
+	core_scheduler().get_priority(tid)
+}
+
+/// Priority inheritance: boost `tid`'s effective priority to `new_prio`
+/// if it is currently scheduled lower
+pub unsafe fn raise_priority(tid: TaskId, new_prio: Priority) {
+	core_scheduler().raise_priority(tid, new_prio)
+}
+
+/// Record that the current task just acquired the mutex guarding `queue`
+pub unsafe fn note_lock_acquired(queue: *const SpinlockIrqSave<PriorityTaskQueue>) {
+	core_scheduler().note_lock_acquired(queue)
+}
+
+/// Record that the current task just released the mutex guarding `queue`
+pub unsafe fn note_lock_released(queue: *const SpinlockIrqSave<PriorityTaskQueue>) {
+	core_scheduler().note_lock_released(queue)
+}
+
+/// Record (or clear) the mutex owner the current task is blocked on
+pub unsafe fn set_blocked_on_owner(owner: Option<TaskId>) {
+	core_scheduler().set_blocked_on_owner(owner)
+}
+
+/// Trigger the scheduler to reschedule the tasks
+pub unsafe fn reschedule() {
+	core_scheduler().reschedule()
+}
+
+/// Voluntarily give up the CPU without blocking, letting another ready
+/// task of the same priority run before this one is scheduled again
+pub unsafe fn yield_now() {
+	core_scheduler().yield_now()
+}
@@ -26,31 +26,45 @@ use core::ptr::Shared;
 use scheduler::task::*;
 use arch::irq::{irq_nested_enable,irq_nested_disable};
 use arch::replace_boot_stack;
+use arch::percore::core_id;
 use logging::*;
 use synch::spinlock::*;
 use alloc::VecDeque;
+use alloc::Vec;
 use alloc::boxed::Box;
 use alloc::btree_map::*;
 
 static TID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// number of tasks managed by the scheduler, across all cores
+static NO_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// map between task id and task control block, shared by every core since
+/// a task may be spawned on one core and run on, or joined from, another
+static TASKS: SpinlockIrqSave<Option<BTreeMap<TaskId, Shared<Task>>>> = SpinlockIrqSave::new(None);
+/// queue of tasks which are finished and can be reclaimed or reused by
+/// `spawn`, shared by every core for the same reason as `TASKS`
+static FINISHED_TASKS: SpinlockIrqSave<Option<VecDeque<TaskId>>> = SpinlockIrqSave::new(None);
 
 extern {
 	pub fn switch(old_stack: *const usize, new_stack: usize);
 }
 
 pub struct Scheduler {
-	/// task which is currently running
-	current_task: Shared<Task>,
-	/// idle task
-	idle_task: Shared<Task>,
-	/// queues of tasks, which are ready
+	/// APIC id of the core this scheduler instance belongs to; written
+	/// once by `add_idle_task` before this core is visible to any other
+	/// core, read-only from then on
+	id: usize,
+	/// task which is currently running on this core
+	current_task: SpinlockIrqSave<Shared<Task>>,
+	/// idle task of this core
+	idle_task: SpinlockIrqSave<Shared<Task>>,
+	/// queue of tasks which are ready to run on this core
 	ready_queue: SpinlockIrqSave<PriorityTaskQueue>,
-	/// queue of tasks, which are finished and can be released
-	finished_tasks: SpinlockIrqSave<Option<VecDeque<TaskId>>>,
-	/// map between task id and task control block
-	tasks: SpinlockIrqSave<Option<BTreeMap<TaskId, Shared<Task>>>>,
-	/// number of tasks managed by the scheduler
-	no_tasks: AtomicUsize
+	/// tasks running on this core which are blocked with a relative
+	/// timeout (`TMO`), ordered by their absolute deadline in ticks
+	timer_queue: SpinlockIrqSave<BTreeMap<usize, VecDeque<Shared<Task>>>>,
+	/// number of timer ticks that have elapsed on this core since boot
+	current_tick: AtomicUsize
 }
 
 impl Scheduler {
@@ -59,12 +73,12 @@ impl Scheduler {
 		Scheduler {
 			// I know that this is unsafe. But I know also that I initialize
 			// the Scheduler (with add_idle_task correctly) before the system schedules task.
-			current_task: unsafe { Shared::new_unchecked(0 as *mut Task) },
-			idle_task: unsafe { Shared::new_unchecked(0 as *mut Task) },
+			id: 0,
+			current_task: SpinlockIrqSave::new(unsafe { Shared::new_unchecked(0 as *mut Task) }),
+			idle_task: SpinlockIrqSave::new(unsafe { Shared::new_unchecked(0 as *mut Task) }),
 			ready_queue: SpinlockIrqSave::new(PriorityTaskQueue::new()),
-			finished_tasks: SpinlockIrqSave::new(None),
-			tasks: SpinlockIrqSave::new(None),
-			no_tasks: AtomicUsize::new(0)
+			timer_queue: SpinlockIrqSave::new(BTreeMap::new()),
+			current_tick: AtomicUsize::new(0)
 		}
 	}
 
@@ -72,42 +86,57 @@ impl Scheduler {
 		loop {
 			let id = TaskId::from(TID_COUNTER.fetch_add(1, Ordering::SeqCst));
 
-			if self.tasks.lock().as_ref().unwrap().contains_key(&id) == false {
+			if TASKS.lock().as_ref().unwrap().contains_key(&id) == false {
 				return id;
 			}
 		}
 	}
 
-	/// add the current task as idle task the scheduler
-	pub unsafe fn add_idle_task(&mut self) {
-		// idle task is the first task for the scheduler => initialize queues and btree
+	/// Register the current (booting) task as the idle task of this core.
+	/// Called once per core during boot / AP bring-up, through the free
+	/// `add_idle_task` function below, which is the only caller ever
+	/// allowed to reach a `Scheduler` through `&mut`: it runs strictly
+	/// before this core's entry in `SCHEDULERS` becomes reachable from any
+	/// other core, so no concurrent `&` can be alive at the same time.
+	unsafe fn add_idle_task(&mut self) {
+		self.id = core_id();
+
+		// the boot core is also responsible for the structures shared by
+		// every core, so initialize them the first time any core gets here
+		{
+			let mut tasks = TASKS.lock();
+			if tasks.is_none() {
+				*tasks = Some(BTreeMap::new());
+				*FINISHED_TASKS.lock() = Some(VecDeque::new());
+			}
+		}
 
-		// initialize vector of queues
-		*self.finished_tasks.lock() = Some(VecDeque::new());
-		*self.tasks.lock() = Some(BTreeMap::new());
 		let tid = self.get_tid();
 
-		// boot task is implicitly task 0 and and the idle task of core 0
+		// each core's boot task is implicitly the idle task of that core
 		let idle_box = Box::new(Task::new(tid, TaskStatus::TaskIdle, LOW_PRIO));
 		let rsp = (*idle_box.stack).bottom();
 		let ist = (*idle_box.ist).bottom();
 		let idle_shared = Shared::new_unchecked(Box::into_raw(idle_box));
+		idle_shared.as_ref().state.lock().core_id = self.id;
 
-		self.idle_task = idle_shared;
-		self.current_task = self.idle_task;
+		*self.idle_task.lock() = idle_shared;
+		*self.current_task.lock() = idle_shared;
 
 		// replace temporary boot stack by the kernel stack of the boot task
 		replace_boot_stack(rsp, ist);
 
-		self.tasks.lock().as_mut().unwrap().insert(tid, idle_shared);
+		TASKS.lock().as_mut().unwrap().insert(tid, idle_shared);
 	}
 
-	/// Spawn a new task
-	pub unsafe fn spawn(&mut self, func: extern fn(), prio: Priority) -> TaskId {
+	/// Spawn a new task and place it on whichever core is currently least
+	/// loaded.
+	pub unsafe fn spawn(&self, func: extern fn(), prio: Priority) -> TaskId {
 		let tid: TaskId;
+		let target = least_loaded_core();
 
 		// do we have finished a task? => reuse it
-		match self.finished_tasks.lock().as_mut().unwrap().pop_front() {
+		match FINISHED_TASKS.lock().as_mut().unwrap().pop_front() {
 			None => {
 				debug!("create new task control block");
 				tid = self.get_tid();
@@ -115,45 +144,82 @@ impl Scheduler {
 
 				task.create_stack_frame(func);
 
-				let shared_task = &mut Shared::new_unchecked(Box::into_raw(task));
-				self.ready_queue.lock().push(prio, shared_task);
-				self.tasks.lock().as_mut().unwrap().insert(tid, *shared_task);
+				let mut shared_task = Shared::new_unchecked(Box::into_raw(task));
+				shared_task.as_ref().state.lock().core_id = target;
+				scheduler_at(target).ready_queue.lock().push(prio, &mut shared_task);
+				TASKS.lock().as_mut().unwrap().insert(tid, shared_task);
 			},
 			Some(id) => {
 				debug!("resuse existing task control block");
 
 				tid = id;
-				match self.tasks.lock().as_mut().unwrap().get_mut(&tid) {
+				match TASKS.lock().as_mut().unwrap().get_mut(&tid) {
 					Some(task) => {
 						// reset old task and setup stack frame
-						task.as_mut().status = TaskStatus::TaskReady;
-						task.as_mut().prio = prio;
+						{
+							let mut st = task.as_ref().state.lock();
+							st.status = TaskStatus::TaskReady;
+							st.base_prio = prio;
+							st.effective_prio = prio;
+							st.held_locks.clear();
+							st.blocked_on_owner = None;
+							st.core_id = target;
+							st.result = None;
+							st.joiners.clear();
+							st.joinable = true;
+						}
 						task.as_mut().last_stack_pointer = 0;
-
 						task.as_mut().create_stack_frame(func);
 
-						self.ready_queue.lock().push(prio, task);
+						scheduler_at(target).ready_queue.lock().push(prio, task);
 					},
 					None => panic!("didn't find task")
 				}
 			}
 		}
 
-		info!("create task with id {}", tid);
+		info!("create task with id {} on core {}", tid, target);
 
 		// update the number of tasks
-		self.no_tasks.fetch_add(1, Ordering::SeqCst);
+		NO_TASKS.fetch_add(1, Ordering::SeqCst);
 
 		tid
 	}
 
+	/// Wakes every task waiting in `join()` for the current task and hands
+	/// out `result` to whoever collects it.
+	unsafe fn finish_current_task(&self, result: Option<usize>) {
+		// `status` and `joiners` are also touched by `join()` (possibly
+		// from a different core) and by `schedule`'s TaskFinished ->
+		// TaskInvalid transition; take the same lock `join` does so a
+		// joiner can't slip past the drain below and block forever
+		let _guard = TASKS.lock();
+		let current = *self.current_task.lock();
+
+		{
+			let mut st = current.as_ref().state.lock();
+			st.result = result;
+			st.status = TaskStatus::TaskFinished;
+		}
+		// update the number of tasks
+		NO_TASKS.fetch_sub(1, Ordering::SeqCst);
+
+		loop {
+			let joiner = current.as_ref().state.lock().joiners.pop_front();
+			match joiner {
+				Some(joiner) => wakeup_task(joiner),
+				None => break
+			}
+		}
+	}
+
 	/// Terminate the current task
-	pub unsafe fn exit(&mut self) -> ! {
-		if self.current_task.as_ref().status != TaskStatus::TaskIdle {
-			info!("finish task with id {}", self.current_task.as_ref().id);
-			self.current_task.as_mut().status = TaskStatus::TaskFinished;
-			// update the number of tasks
-			self.no_tasks.fetch_sub(1, Ordering::SeqCst);
+	pub unsafe fn exit(&self, result: usize) -> ! {
+		let current = *self.current_task.lock();
+
+		if current.as_ref().state.lock().status != TaskStatus::TaskIdle {
+			info!("finish task with id {}", current.as_ref().id);
+			self.finish_current_task(Some(result));
 		} else {
 			panic!("unable to terminate idle task");
 		}
@@ -164,61 +230,263 @@ impl Scheduler {
 		panic!("exit failed!")
 	}
 
-	pub unsafe fn abort(&mut self) -> ! {
-			if self.current_task.as_ref().status != TaskStatus::TaskIdle {
-				info!("abort task with id {}", self.current_task.as_ref().id);
-				self.current_task.as_mut().status = TaskStatus::TaskFinished;
-				// update the number of tasks
-				self.no_tasks.fetch_sub(1, Ordering::SeqCst);
+	pub unsafe fn abort(&self) -> ! {
+		let current = *self.current_task.lock();
+
+		if current.as_ref().state.lock().status != TaskStatus::TaskIdle {
+			info!("abort task with id {}", current.as_ref().id);
+			self.finish_current_task(None);
+		} else {
+			panic!("unable to terminate idle task");
+		}
+
+		self.reschedule();
+
+		// we should never reach this point
+		panic!("abort failed!");
+	}
+
+	/// Block the caller until task `tid` reaches `TaskFinished` and return
+	/// its exit value (`0` for a task that was aborted, or that no longer
+	/// exists).
+	pub unsafe fn join(&self, tid: TaskId) -> usize {
+		loop {
+			let should_block;
+
+			{
+				let mut guard = TASKS.lock();
+				match guard.as_mut().unwrap().get_mut(&tid) {
+					Some(task) => {
+						let mut st = task.as_ref().state.lock();
+
+						// `TaskInvalid` is the state `schedule` moves a
+						// `TaskFinished` task to once it has been switched
+						// away from; a joiner arriving this late missed
+						// the one-time drain in `finish_current_task` and
+						// must not enqueue itself, or it would block
+						// forever waiting on a wakeup that already happened
+						if st.status == TaskStatus::TaskFinished
+							|| st.status == TaskStatus::TaskInvalid {
+							should_block = false;
+						} else {
+							let current = *self.current_task.lock();
+							st.joiners.push_back(current);
+							drop(st);
+							current.as_ref().state.lock().status = TaskStatus::TaskBlocked;
+							should_block = true;
+						}
+					},
+					None => return 0
+				}
+			}
+
+			if should_block {
+				self.reschedule();
 			} else {
-				panic!("unable to terminate idle task");
+				break;
 			}
+		}
 
-			self.reschedule();
+		match TASKS.lock().as_mut().unwrap().remove(&tid) {
+			Some(task) => {
+				let result = task.as_ref().state.lock().result.unwrap_or(0);
+				drop(Box::from_raw(task.as_ptr()));
+				result
+			},
+			None => 0
+		}
+	}
+
+	/// Mark task `tid` as detached: if it has already finished, reclaim it
+	/// immediately, otherwise let it fall back to the usual
+	/// reuse-on-finish behaviour instead of waiting to be joined.
+	pub unsafe fn detach(&self, tid: TaskId) {
+		let finished = {
+			let mut guard = TASKS.lock();
+			match guard.as_mut().unwrap().get_mut(&tid) {
+				Some(task) => {
+					let mut st = task.as_ref().state.lock();
+
+					// a task can already be `TaskInvalid` by the time we
+					// get here (it finished and `schedule` switched away
+					// from it before this handle was dropped); in that
+					// case `schedule`'s one-time reclaim-or-keep decision
+					// was already made with `joinable == true`, so nothing
+					// else will ever free this TCB unless we do it here
+					if st.status == TaskStatus::TaskFinished
+						|| st.status == TaskStatus::TaskInvalid {
+						true
+					} else {
+						st.joinable = false;
+						false
+					}
+				},
+				None => return
+			}
+		};
 
-			// we should never reach this point
-			panic!("abort failed!");
+		if finished {
+			if let Some(task) = TASKS.lock().as_mut().unwrap().remove(&tid) {
+				drop(Box::from_raw(task.as_ptr()));
+			}
+		}
 	}
 
 	pub fn number_of_tasks(&self) -> usize {
-		self.no_tasks.load(Ordering::SeqCst)
+		NO_TASKS.load(Ordering::SeqCst)
 	}
 
 	/// Block the current task
-	pub unsafe fn block_current_task(&mut self) -> Shared<Task> {
-		if self.current_task.as_ref().status == TaskStatus::TaskRunning {
-			debug!("block task {}", self.current_task.as_ref().id);
+	pub unsafe fn block_current_task(&self) -> Shared<Task> {
+		let current = *self.current_task.lock();
+		let mut st = current.as_ref().state.lock();
+
+		if st.status == TaskStatus::TaskRunning {
+			debug!("block task {}", current.as_ref().id);
 
-			self.current_task.as_mut().status = TaskStatus::TaskBlocked;
-			return self.current_task;
+			st.status = TaskStatus::TaskBlocked;
+			drop(st);
+			current
 		} else {
-			panic!("unable to block task {}", self.current_task.as_ref().id);
+			panic!("unable to block task {}", current.as_ref().id);
 		}
 	}
 
-	/// Wakeup a blocked task
-	pub unsafe fn wakeup_task(&mut self, mut task: Shared<Task>) {
-		if task.as_ref().status == TaskStatus::TaskBlocked {
-			let prio = task.as_ref().prio;
+	/// Block the current task until `ticks` timer ticks have elapsed,
+	/// mirroring the microITRON `TMO` (relative timeout) model.
+	pub unsafe fn block_current_task_timeout(&self, ticks: usize) -> Shared<Task> {
+		let current = *self.current_task.lock();
+		let deadline = self.current_tick.load(Ordering::SeqCst) + ticks;
+		let mut st = current.as_ref().state.lock();
 
-			debug!("wakeup task {}", task.as_ref().id);
+		if st.status == TaskStatus::TaskRunning {
+			debug!("block task {} until tick {}", current.as_ref().id, deadline);
 
-			task.as_mut().status = TaskStatus::TaskReady;
-			self.ready_queue.lock().push(prio, &mut Shared::new_unchecked(task.as_mut()));
+			st.status = TaskStatus::TaskBlockedUntil(deadline);
+			st.timed_out = false;
+			st.core_id = self.id;
+			drop(st);
+
+			self.timer_queue.lock().entry(deadline).or_insert_with(VecDeque::new).push_back(current);
+
+			current
+		} else {
+			panic!("unable to block task {}", current.as_ref().id);
+		}
+	}
+
+	/// Determine (and reset) whether the current task's last block was
+	/// ended by a timeout rather than an explicit `wakeup_task`.
+	pub unsafe fn current_task_timed_out(&self) -> bool {
+		let current = *self.current_task.lock();
+		let mut st = current.as_ref().state.lock();
+		let timed_out = st.timed_out;
+		st.timed_out = false;
+		timed_out
+	}
+
+	/// Wakeup a blocked task that is known to be homed on this core, i.e.
+	/// queued in this core's `timer_queue` if it carries a timeout.
+	/// Dispatched to by the free `wakeup_task` function, which picks the
+	/// destination `ready_queue` (not necessarily this core's).
+	unsafe fn wakeup_task(&self, mut task: Shared<Task>, target: usize) {
+		// the status check and the TaskReady transition it leads to must
+		// happen under a single lock acquisition: `timer_tick` can be
+		// racing us to wake up this very same task, and if we read
+		// `status` and acted on it as two separate critical sections,
+		// both sides could observe the pre-transition state and enqueue
+		// the task twice
+		let (prio, deadline) = {
+			let mut st = task.as_ref().state.lock();
+
+			match st.status {
+				TaskStatus::TaskBlocked => {
+					st.status = TaskStatus::TaskReady;
+					st.core_id = target;
+					(st.effective_prio, None)
+				},
+				TaskStatus::TaskBlockedUntil(deadline) => {
+					st.status = TaskStatus::TaskReady;
+					st.core_id = target;
+					(st.effective_prio, Some(deadline))
+				},
+				_ => return
+			}
+		};
+
+		debug!("wakeup task {}", task.as_ref().id);
+
+		if let Some(deadline) = deadline {
+			// remove the task from the timer queue of the core that
+			// parked it; we already won the race above (status is
+			// `TaskReady` now), so even if `timer_tick` gets to this
+			// deadline first it will see the status mismatch and skip
+			// the task instead of enqueuing it a second time
+			let id = task.as_ref().id;
+			let mut is_empty = false;
+			if let Some(waiters) = self.timer_queue.lock().get_mut(&deadline) {
+				waiters.retain(|t| t.as_ref().id != id);
+				is_empty = waiters.is_empty();
+			}
+			if is_empty {
+				self.timer_queue.lock().remove(&deadline);
+			}
+		}
+
+		scheduler_at(target).ready_queue.lock().push(prio, &mut Shared::new_unchecked(task.as_mut()));
+	}
+
+	/// Called by the timer interrupt handler on every tick of this core.
+	/// Wakes up all tasks on this core whose relative timeout has elapsed.
+	pub unsafe fn timer_tick(&self) {
+		let now = self.current_tick.fetch_add(1, Ordering::SeqCst) + 1;
+
+		let due: Vec<usize> = self.timer_queue.lock().iter()
+			.filter(|&(&deadline, _)| deadline <= now)
+			.map(|(&deadline, _)| deadline)
+			.collect();
+
+		for deadline in due {
+			if let Some(mut waiters) = self.timer_queue.lock().remove(&deadline) {
+				while let Some(mut task) = waiters.pop_front() {
+					let target = least_loaded_core();
+
+					let prio = {
+						let mut st = task.as_ref().state.lock();
+
+						// a concurrent wakeup_task may have already claimed
+						// this task under its own lock (e.g. the mutex it
+						// was waiting on got released just before the
+						// timeout fired); if so, it already enqueued the
+						// task, and we must not enqueue the same TCB again
+						if st.status != TaskStatus::TaskBlockedUntil(deadline) {
+							continue;
+						}
+
+						st.timed_out = true;
+						st.status = TaskStatus::TaskReady;
+						st.core_id = target;
+						st.effective_prio
+					};
+
+					scheduler_at(target).ready_queue.lock().push(prio, &mut task);
+				}
+			}
 		}
 	}
 
 	/// Determines the id of the current task
 	#[inline(always)]
 	pub fn get_current_taskid(&self) -> TaskId {
-		unsafe { self.current_task.as_ref().id }
+		unsafe { self.current_task.lock().as_ref().id }
 	}
 
 	/// Determines the start addresses of the stacks
 	#[inline(always)]
 	pub fn get_current_stacks(&self) -> (usize, usize) {
 		unsafe {
-			((*self.current_task.as_ref().stack).bottom(), (*self.current_task.as_ref().ist).bottom())
+			let current = *self.current_task.lock();
+			((*current.as_ref().stack).bottom(), (*current.as_ref().ist).bottom())
 		}
 	}
 
@@ -226,78 +494,200 @@ impl Scheduler {
 	#[inline(always)]
 	pub fn get_kernel_stack(&self) -> usize {
 		unsafe {
-			(*self.current_task.as_ref().stack).bottom()
+			(*self.current_task.lock().as_ref().stack).bottom()
 		}
 	}
 
-	/// Determines the priority of the current task
+	/// Determines the (effective, possibly boosted) priority of the current task
 	#[inline(always)]
 	pub fn get_current_priority(&self) -> Priority {
-		unsafe { self.current_task.as_ref().prio }
+		unsafe { self.current_task.lock().as_ref().state.lock().effective_prio }
 	}
 
-	/// Determines the priority of the task with the 'tid'
+	/// Determines the effective priority of the task with the 'tid'
 	pub fn get_priority(&self, tid: TaskId) -> Priority {
 		let mut prio: Priority = NORMAL_PRIO;
 
-		match self.tasks.lock().as_ref().unwrap().get(&tid) {
-			Some(task) => prio = unsafe { task.as_ref().prio },
+		match TASKS.lock().as_ref().unwrap().get(&tid) {
+			Some(task) => prio = unsafe { task.as_ref().state.lock().effective_prio },
 			None => { info!("didn't find current task"); }
 		}
 
 		prio
 	}
 
-	unsafe fn get_next_task(&mut self) -> Option<Shared<Task>> {
-		let mut prio = LOW_PRIO;
-		let status: TaskStatus;
+	/// Boost `tid`'s effective priority to `new_prio` if it is currently
+	/// lower (priority inheritance). If `tid` is sitting in a ready queue
+	/// it is moved to its new priority band on whichever core's queue it
+	/// is in, and if it is itself blocked waiting on another mutex, the
+	/// boost is propagated to that mutex's owner in turn.
+	pub unsafe fn raise_priority(&self, tid: TaskId, new_prio: Priority) {
+		let mut task = match TASKS.lock().as_ref().unwrap().get(&tid) {
+			Some(task) => *task,
+			None => return
+		};
+
+		// the whole read-modify-write has to happen under the task's own
+		// lock, or a concurrent `raise_priority`/`wakeup_task` from another
+		// core could act on a priority or status that is already stale
+		let (old_prio, status, blocked_on_owner, core_id) = {
+			let mut st = task.as_ref().state.lock();
+
+			if new_prio <= st.effective_prio {
+				return;
+			}
+
+			let old_prio = st.effective_prio;
+			st.effective_prio = new_prio;
+			(old_prio, st.status, st.blocked_on_owner, st.core_id)
+		};
 
-		// if the current task is runable, check only if a task with
-		// higher priority is available
-		if self.current_task.as_ref().status == TaskStatus::TaskRunning {
-			prio = self.current_task.as_ref().prio;
+		if status == TaskStatus::TaskReady {
+			let home = scheduler_at(core_id);
+			if home.ready_queue.lock().remove(old_prio, tid) {
+				home.ready_queue.lock().push(new_prio, &mut task);
+			}
 		}
-		status = self.current_task.as_ref().status;
 
-		match self.ready_queue.lock().pop_with_prio(prio) {
-			Some(mut task) => {
-				task.as_mut().status = TaskStatus::TaskRunning;
-				return Some(task)
+		// the task we just boosted might itself be blocked waiting on a
+		// different mutex => keep walking the chain
+		match status {
+			TaskStatus::TaskBlocked | TaskStatus::TaskBlockedUntil(_) => {
+				if let Some(next_owner) = blocked_on_owner {
+					self.raise_priority(next_owner, new_prio);
+				}
 			},
-			None => {}
+			_ => {}
+		}
+	}
+
+	/// Record that the current task just became the owner of the mutex
+	/// guarding `queue`, so its effective priority can be recomputed once
+	/// the lock is released again.
+	pub unsafe fn note_lock_acquired(&self, queue: *const SpinlockIrqSave<PriorityTaskQueue>) {
+		let current = *self.current_task.lock();
+		current.as_ref().state.lock().held_locks.push(queue);
+	}
+
+	/// Record that the current task just released the mutex guarding
+	/// `queue`, and drop its effective priority back to whatever is still
+	/// warranted by the locks it has left (or its base priority, if none).
+	pub unsafe fn note_lock_released(&self, queue: *const SpinlockIrqSave<PriorityTaskQueue>) {
+		let current = *self.current_task.lock();
+		let mut st = current.as_ref().state.lock();
+
+		if let Some(pos) = st.held_locks.iter().position(|&q| q == queue) {
+			st.held_locks.remove(pos);
+		}
+
+		let mut prio = st.base_prio;
+		for &q in st.held_locks.iter() {
+			if let Some(waiter_prio) = (*q).lock().peek_max_prio() {
+				if waiter_prio > prio {
+					prio = waiter_prio;
+				}
+			}
 		}
 
-		if status != TaskStatus::TaskRunning && status != TaskStatus::TaskIdle {
+		st.effective_prio = prio;
+	}
+
+	/// Record (or clear) the mutex owner the current task is blocked on,
+	/// so `raise_priority` can propagate a boost along a chain of nested
+	/// locks.
+	pub unsafe fn set_blocked_on_owner(&self, owner: Option<TaskId>) {
+		let current = *self.current_task.lock();
+		current.as_ref().state.lock().blocked_on_owner = owner;
+	}
+
+	unsafe fn get_next_task(&self) -> Option<Shared<Task>> {
+		let current = *self.current_task.lock();
+		let (current_status, current_prio) = {
+			let st = current.as_ref().state.lock();
+			(st.status, st.effective_prio)
+		};
+
+		// if the current task is runable, only switch away if a task of at
+		// least its own priority is waiting (the FIFO tiebreak in
+		// pop_with_prio keeps same-priority tasks from starving each other)
+		let mut prio = LOW_PRIO;
+		if current_status == TaskStatus::TaskRunning {
+			prio = current_prio;
+		}
+
+		if let Some(task) = self.ready_queue.lock().pop_with_prio(prio) {
+			task.as_ref().state.lock().status = TaskStatus::TaskRunning;
+			return Some(task);
+		}
+
+		// our local queue has nothing suitable => try to steal ready work
+		// from a sibling core before giving up and running idle
+		for other in 0..MAX_CORES {
+			if other == self.id {
+				continue;
+			}
+
+			if let Some(task) = scheduler_at(other).ready_queue.lock().pop_with_prio(prio) {
+				debug!("core {} stole task {} from core {}", self.id, task.as_ref().id, other);
+				let mut st = task.as_ref().state.lock();
+				st.status = TaskStatus::TaskRunning;
+				st.core_id = self.id;
+				drop(st);
+				return Some(task);
+			}
+		}
+
+		if current_status != TaskStatus::TaskRunning && current_status != TaskStatus::TaskIdle {
 			// current task isn't able to run and no other task available
 			// => switch to the idle task
-			Some(self.idle_task)
+			Some(*self.idle_task.lock())
 		} else {
 			None
 		}
 	}
 
-	pub unsafe fn schedule(&mut self) {
+	pub unsafe fn schedule(&self) {
 		// do we have a task, which is ready?
 		match self.get_next_task() {
 			Some(next_task) => {
-				let old_id: TaskId = self.current_task.as_ref().id;
-
-				if self.current_task.as_ref().status == TaskStatus::TaskRunning {
-					self.current_task.as_mut().status = TaskStatus::TaskReady;
-					self.ready_queue.lock().push(self.current_task.as_ref().prio,
-						&mut self.current_task);
-				} else if self.current_task.as_ref().status == TaskStatus::TaskFinished {
-					self.current_task.as_mut().status = TaskStatus::TaskInvalid;
-					// release the task later, because the stack is required
-					// to call the function "switch"
-					// => push id to a queue and release the task later
-					self.finished_tasks.lock().as_mut().unwrap().push_back(old_id);
+				let mut current = *self.current_task.lock();
+				let old_id: TaskId = current.as_ref().id;
+				let current_status = current.as_ref().state.lock().status;
+
+				if current_status == TaskStatus::TaskRunning {
+					let prio = {
+						let mut st = current.as_ref().state.lock();
+						st.status = TaskStatus::TaskReady;
+						st.core_id = self.id;
+						st.effective_prio
+					};
+					self.ready_queue.lock().push(prio, &mut current);
+				} else if current_status == TaskStatus::TaskFinished {
+					// same lock `join`/`finish_current_task` use: a joiner
+					// reading `status` must see either the pre- or the
+					// post-transition state, never something in between
+					let _guard = TASKS.lock();
+					let joinable = {
+						let mut st = current.as_ref().state.lock();
+						st.status = TaskStatus::TaskInvalid;
+						st.joinable
+					};
+
+					if joinable {
+						// someone may still call `join` on this task, so it
+						// has to stay in `TASKS` until it is collected
+					} else {
+						// release the task later, because the stack is required
+						// to call the function "switch"
+						// => push id to a queue and release the task later
+						FINISHED_TASKS.lock().as_mut().unwrap().push_back(old_id);
+					}
 				}
 
 				let next_stack_pointer = next_task.as_ref().last_stack_pointer;
-				let old_stack_pointer = &self.current_task.as_ref().last_stack_pointer as *const usize;
+				let old_stack_pointer = &current.as_ref().last_stack_pointer as *const usize;
 
-				self.current_task = next_task;
+				*self.current_task.lock() = next_task;
 
 				debug!("switch task from {} to {}", old_id, next_task.as_ref().id);
 
@@ -308,12 +698,17 @@ impl Scheduler {
 	}
 
 	/// Check if a finisched task could be deleted.
-	unsafe fn cleanup_tasks(&mut self)
+	unsafe fn cleanup_tasks(&self)
 	{
 		// do we have finished tasks? => drop first tasks => deallocate implicitly the stack
-		match self.finished_tasks.lock().as_mut().unwrap().pop_front() {
+		//
+		// `FINISHED_TASKS` is shared by every core, so whichever core next
+		// calls `reschedule` may end up freeing a task that last ran on a
+		// different core; that is fine, the task's stack is no longer in
+		// use by the time it got here.
+		match FINISHED_TASKS.lock().as_mut().unwrap().pop_front() {
 			Some(id) => {
-				match self.tasks.lock().as_mut().unwrap().remove(&id) {
+				match TASKS.lock().as_mut().unwrap().remove(&id) {
 					Some(task) => drop(Box::from_raw(task.as_ptr())),
 					None => info!("unable to drop task {}", id)
 				}
@@ -324,7 +719,7 @@ impl Scheduler {
 
 	/// Triggers the scheduler to reschedule the tasks
 	#[inline(always)]
-	pub unsafe fn reschedule(&mut self) {
+	pub unsafe fn reschedule(&self) {
 		// someone want to give up the CPU
 		// => we have time to cleanup the system
 		self.cleanup_tasks();
@@ -333,4 +728,72 @@ impl Scheduler {
 		self.schedule();
 		irq_nested_enable(flags);
 	}
+
+	/// Voluntarily give up the CPU while remaining ready to run. Unlike
+	/// blocking, the current task stays `TaskReady` and needs no external
+	/// wakeup: `schedule` re-inserts it at the tail of its priority band,
+	/// and the FIFO tiebreak in `pop_with_prio` guarantees that any other
+	/// task of the same priority already waiting runs before it is picked
+	/// again.
+	pub unsafe fn yield_now(&self) {
+		self.reschedule();
+	}
+}
+
+/// Maximum number of cores this teaching kernel supports; large enough for
+/// any machine eduOS-rs is likely to boot on, small enough to keep the
+/// per-core scheduler table a plain static array.
+pub const MAX_CORES: usize = 8;
+
+/// one scheduler instance per core, indexed by APIC id
+static mut SCHEDULERS: [Scheduler; MAX_CORES] = [
+	Scheduler::new(), Scheduler::new(), Scheduler::new(), Scheduler::new(),
+	Scheduler::new(), Scheduler::new(), Scheduler::new(), Scheduler::new()
+];
+
+/// Register the current (booting) task as the idle task of this core. Must
+/// run once on every core, including each application processor as it is
+/// brought up, before that core's entry in `SCHEDULERS` is ever reached
+/// from another core: this is the only place in the scheduler that takes a
+/// `&mut Scheduler`, and it relies on that ordering to be sound, since
+/// every other access (`scheduler_at`, `this_scheduler`) only ever hands
+/// out a shared `&Scheduler`.
+pub unsafe fn add_idle_task() {
+	SCHEDULERS[core_id()].add_idle_task();
+}
+
+#[inline(always)]
+fn scheduler_at(id: usize) -> &'static Scheduler {
+	unsafe { &SCHEDULERS[id] }
+}
+
+/// The scheduler instance of the currently executing core.
+#[inline(always)]
+pub fn this_scheduler() -> &'static Scheduler {
+	scheduler_at(unsafe { core_id() })
+}
+
+/// Id of the core whose `ready_queue` currently holds the fewest tasks;
+/// used to spread newly spawned and newly woken tasks across cores.
+fn least_loaded_core() -> usize {
+	let mut best = 0;
+	let mut best_len = usize::max_value();
+
+	for id in 0..MAX_CORES {
+		let len = scheduler_at(id).ready_queue.lock().len();
+		if len < best_len {
+			best = id;
+			best_len = len;
+		}
+	}
+
+	best
+}
+
+/// Wakeup a previously blocked task, placing it on whichever core is
+/// currently least loaded.
+pub unsafe fn wakeup_task(task: Shared<Task>) {
+	let home = task.as_ref().state.lock().core_id;
+	let target = least_loaded_core();
+	scheduler_at(home).wakeup_task(task, target);
 }
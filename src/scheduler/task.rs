@@ -0,0 +1,250 @@
+// Copyright (c) 2017 Stefan Lankes, RWTH Aachen University
+//
+// MIT License
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use core::fmt;
+use core::ptr::Shared;
+use alloc::boxed::Box;
+use alloc::VecDeque;
+use alloc::Vec;
+use arch::scheduler::{Stack, create_stack_frame, DEFAULT_STACK_SIZE, KERNEL_STACK_SIZE};
+use synch::spinlock::SpinlockIrqSave;
+
+/// A priority, lower numbers mean less important tasks.
+pub type Priority = u8;
+
+/// Priority of the idle task, reserved for the per-core idle loop.
+pub const LOW_PRIO: Priority = 0;
+/// Default priority of a newly spawned task.
+pub const NORMAL_PRIO: Priority = 16;
+/// Highest priority a task can have.
+pub const HIGH_PRIO: Priority = 31;
+
+const NO_PRIORITIES: usize = HIGH_PRIO as usize + 1;
+
+/// Unique identifier of a task
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TaskId(usize);
+
+impl From<usize> for TaskId {
+	fn from(x: usize) -> Self {
+		TaskId(x)
+	}
+}
+
+impl From<TaskId> for usize {
+	fn from(x: TaskId) -> Self {
+		x.0
+	}
+}
+
+impl fmt::Display for TaskId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// Current lifecycle state of a task
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TaskStatus {
+	TaskIdle,
+	TaskRunning,
+	TaskReady,
+	TaskBlocked,
+	/// blocked with a relative timeout (`TMO`); holds the absolute
+	/// deadline, in scheduler ticks, at which the task must be woken
+	/// even if nobody calls `wakeup_task`
+	TaskBlockedUntil(usize),
+	TaskFinished,
+	TaskInvalid
+}
+
+/// The part of a task's control block that is read and written from
+/// whichever core happens to touch the task at a given moment (its home
+/// core while it runs or sits in a local queue, any core that wakes it,
+/// steals it, or walks a priority-inheritance chain into it). Grouped
+/// behind a single lock so that a status check and the transition it
+/// leads to (e.g. `TaskBlockedUntil` -> `TaskReady`) are always atomic,
+/// instead of being spread across several unsynchronized field accesses.
+pub struct TaskState {
+	/// current status of the task
+	pub status: TaskStatus,
+	/// priority the task was created with; a released mutex restores the
+	/// task's `effective_prio` to this level once it holds no more locks
+	pub base_prio: Priority,
+	/// priority the task is scheduled with; normally equal to `base_prio`,
+	/// but may be boosted above it by priority inheritance while the task
+	/// owns a mutex that a higher-priority task is waiting for
+	pub effective_prio: Priority,
+	/// wait queues of the mutexes this task currently holds, used to
+	/// recompute `effective_prio` when one of them is released
+	pub held_locks: Vec<*const SpinlockIrqSave<PriorityTaskQueue>>,
+	/// if this task is blocked waiting on a mutex, the task that currently
+	/// owns it; used to propagate priority inheritance across a chain of
+	/// nested locks
+	pub blocked_on_owner: Option<TaskId>,
+	/// id of the core whose `ready_queue` last queued this task (or, while
+	/// blocked with a timeout, the core whose `timer_queue` is holding it);
+	/// lets `raise_priority` and an early `wakeup_task` find the right
+	/// per-core queue to touch without searching every core
+	pub core_id: usize,
+	/// set by the timer tick when a `TaskBlockedUntil` deadline fires, so
+	/// that the caller of `block_current_task_timeout` can tell a timeout
+	/// apart from a regular `wakeup_task`
+	pub timed_out: bool,
+	/// exit value stashed by `exit()`, readable once `status` becomes
+	/// `TaskFinished`
+	pub result: Option<usize>,
+	/// tasks currently blocked in `join()` on this task
+	pub joiners: VecDeque<Shared<Task>>,
+	/// whether a finished task must be kept around for `join()` to collect
+	/// instead of being reclaimed immediately (cleared by dropping the
+	/// task's `JoinHandle` without joining it; a joined task is freed by
+	/// `join` instead)
+	pub joinable: bool
+}
+
+/// Task control block
+pub struct Task {
+	/// the task's unique id, fixed for the task's whole lifetime
+	pub id: TaskId,
+	/// everything about the task that can be touched from more than one
+	/// core; see `TaskState`
+	pub state: SpinlockIrqSave<TaskState>,
+	/// last stack pointer before a context switch to this task; only ever
+	/// read or written by the one core currently running or switching
+	/// into/out of the task, so it needs no lock of its own
+	pub last_stack_pointer: usize,
+	/// stack of the task
+	pub stack: *mut Stack,
+	/// stack to handle interrupts
+	pub ist: *mut Stack
+}
+
+impl Task {
+	pub fn new(id: TaskId, status: TaskStatus, prio: Priority) -> Task {
+		Task {
+			id: id,
+			state: SpinlockIrqSave::new(TaskState {
+				status: status,
+				base_prio: prio,
+				effective_prio: prio,
+				held_locks: Vec::new(),
+				blocked_on_owner: None,
+				core_id: 0,
+				timed_out: false,
+				result: None,
+				joiners: VecDeque::new(),
+				joinable: true
+			}),
+			last_stack_pointer: 0,
+			stack: Box::into_raw(Box::new(Stack::new(DEFAULT_STACK_SIZE))),
+			ist: Box::into_raw(Box::new(Stack::new(KERNEL_STACK_SIZE)))
+		}
+	}
+
+	pub unsafe fn create_stack_frame(&mut self, func: extern fn()) {
+		self.last_stack_pointer = create_stack_frame(self.stack, func);
+	}
+}
+
+/// A run queue which keeps ready tasks ordered by priority.
+///
+/// Tasks of the same priority are served FIFO, which also makes the queue
+/// usable as a simple wait queue for `Mutex`, `Condvar` and `Semaphore`.
+pub struct PriorityTaskQueue {
+	queues: Vec<VecDeque<Shared<Task>>>
+}
+
+impl PriorityTaskQueue {
+	pub fn new() -> PriorityTaskQueue {
+		let mut queues = Vec::with_capacity(NO_PRIORITIES);
+		for _ in 0..NO_PRIORITIES {
+			queues.push(VecDeque::new());
+		}
+
+		PriorityTaskQueue { queues: queues }
+	}
+
+	/// Push a task at the back of its priority band.
+	pub fn push(&mut self, prio: Priority, task: &mut Shared<Task>) {
+		self.queues[prio as usize].push_back(*task);
+	}
+
+	/// Pop the task with the highest priority, ignoring `prio`.
+	pub fn pop(&mut self) -> Option<Shared<Task>> {
+		for i in (0..NO_PRIORITIES).rev() {
+			if let Some(task) = self.queues[i].pop_front() {
+				return Some(task);
+			}
+		}
+
+		None
+	}
+
+	/// Pop a task only if one with a priority at least `prio` is waiting.
+	/// Tasks of equal priority are returned in FIFO order, which is what
+	/// lets `yield_now` actually rotate among same-priority tasks instead
+	/// of being handed straight back the CPU.
+	pub fn pop_with_prio(&mut self, prio: Priority) -> Option<Shared<Task>> {
+		for i in ((prio as usize)..NO_PRIORITIES).rev() {
+			if let Some(task) = self.queues[i].pop_front() {
+				return Some(task);
+			}
+		}
+
+		None
+	}
+
+	/// Remove a specific task from its priority band, e.g. because it is
+	/// about to be re-inserted at a boosted priority. Returns whether it
+	/// was found.
+	pub fn remove(&mut self, prio: Priority, id: TaskId) -> bool {
+		let band = &mut self.queues[prio as usize];
+
+		match band.iter().position(|task| unsafe { task.as_ref().id } == id) {
+			Some(pos) => {
+				band.remove(pos);
+				true
+			},
+			None => false
+		}
+	}
+
+	/// Total number of tasks currently queued, across all priority bands.
+	/// Used to compare how loaded two cores' run queues are for
+	/// load-balanced `spawn`/`wakeup_task` placement.
+	pub fn len(&self) -> usize {
+		self.queues.iter().map(|q| q.len()).sum()
+	}
+
+	/// Highest priority band that currently has a waiting task, if any.
+	pub fn peek_max_prio(&self) -> Option<Priority> {
+		for i in (0..NO_PRIORITIES).rev() {
+			if !self.queues[i].is_empty() {
+				return Some(i as Priority);
+			}
+		}
+
+		None
+	}
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2017 Stefan Lankes, RWTH Aachen University
+//
+// MIT License
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use scheduler::task::*;
+use scheduler::{reschedule, block_current_task, get_current_priority, note_lock_released, wakeup_task};
+use synch::spinlock::*;
+use synch::mutex::{acquire, MutexGuard};
+use arch::irq::{irq_nested_disable, irq_nested_enable};
+
+/// A condition variable, modeled on the wait-queue style condvar/wait
+/// primitives of a microITRON kernel.
+///
+/// A `Condvar` is always used together with a `Mutex`: `wait` atomically
+/// releases the mutex and blocks the current task, and re-acquires the
+/// mutex before returning. Since wakeups may be spurious, callers must
+/// re-check their predicate in a loop:
+///
+/// ```
+/// let mut guard = mutex.lock();
+/// while !*guard {
+///     guard = condvar.wait(guard);
+/// }
+/// ```
+pub struct Condvar {
+	/// priority queue of tasks waiting on this condition
+	waiters: SpinlockIrqSave<PriorityTaskQueue>
+}
+
+impl Condvar {
+	pub const fn new() -> Condvar {
+		Condvar {
+			waiters: SpinlockIrqSave::new(PriorityTaskQueue::new())
+		}
+	}
+
+	/// Block the current task on this condition variable, releasing
+	/// `guard`'s mutex while blocked and re-acquiring it before returning.
+	pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+		let (value, queue, data) = guard.into_raw_parts();
+
+		unsafe {
+			// everything up to and including `reschedule()` has to happen
+			// in one critical section: if we released the mutex before
+			// registering ourselves as a waiter, a `notify` could run
+			// between the two and we would sleep through it.
+			let flags = irq_nested_disable();
+			self.waiters.lock().push(get_current_priority(), &mut block_current_task());
+
+			let mut owner = value.lock();
+			*owner = None;
+			note_lock_released(queue as *const _);
+			// the mutex itself may already have its own waiters, wake one
+			// of them now that it is free again
+			if let Some(task) = queue.lock().pop() {
+				wakeup_task(task);
+			}
+			drop(owner);
+
+			reschedule();
+			irq_nested_enable(flags);
+		}
+
+		// re-acquire the mutex before handing the guard back, just like
+		// `Mutex::lock` would
+		acquire(value, queue);
+
+		MutexGuard { value: value, queue: queue, data: data }
+	}
+
+	/// Wake up one waiting task, if any.
+	pub fn notify_one(&self) {
+		if let Some(task) = self.waiters.lock().pop() {
+			unsafe { wakeup_task(task); }
+		}
+	}
+
+	/// Wake up all waiting tasks.
+	pub fn notify_all(&self) {
+		while let Some(task) = self.waiters.lock().pop() {
+			unsafe { wakeup_task(task); }
+		}
+	}
+}
+
+unsafe impl Send for Condvar {}
+unsafe impl Sync for Condvar {}
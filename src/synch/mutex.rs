@@ -24,8 +24,10 @@
 use core::cell::UnsafeCell;
 use core::ops::{Drop, Deref, DerefMut};
 use core::marker::Sync;
+use core::mem;
+use core::ptr;
 use scheduler::task::*;
-use scheduler::{reschedule,block_current_task,get_current_priority,wakeup_task};
+use scheduler::{reschedule,block_current_task,block_current_task_timeout,current_task_timed_out,get_current_priority,get_current_taskid,note_lock_acquired,note_lock_released,raise_priority,set_blocked_on_owner,wakeup_task};
 use synch::spinlock::*;
 
 /// A mutual exclusion primitive useful for protecting shared data
@@ -58,10 +60,11 @@ use synch::spinlock::*;
 /// assert_eq!(answer, 2);
 /// ```
 pub struct Mutex<T: ?Sized> {
-	/// in principle a binary semaphore
-	value: SpinlockIrqSave<bool>,
+	/// owner of the lock, or `None` while it is free; doubles as the
+	/// binary semaphore that used to guard the data
+	pub(crate) value: SpinlockIrqSave<Option<TaskId>>,
 	/// Priority queue of waiting tasks
-	queue: SpinlockIrqSave<PriorityTaskQueue>,
+	pub(crate) queue: SpinlockIrqSave<PriorityTaskQueue>,
 	/// protected data
 	data: UnsafeCell<T>
 }
@@ -70,15 +73,20 @@ pub struct Mutex<T: ?Sized> {
 ///
 /// When the guard falls out of scope it will release the lock.
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
-	value: &'a SpinlockIrqSave<bool>,
-	queue: &'a SpinlockIrqSave<PriorityTaskQueue>,
-	data: &'a mut T
+	pub(crate) value: &'a SpinlockIrqSave<Option<TaskId>>,
+	pub(crate) queue: &'a SpinlockIrqSave<PriorityTaskQueue>,
+	pub(crate) data: &'a mut T
 }
 
 // Same unsafe impls as `Mutex`
 unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 
+/// Returned by `Mutex::lock_timeout` when the lock could not be obtained
+/// before the requested number of ticks elapsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimedOut;
+
 impl<T> Mutex<T> {
 	/// Creates a new semaphore with the initial count specified.
 	///
@@ -87,7 +95,7 @@ impl<T> Mutex<T> {
 	/// available. It is valid to initialize a semaphore with a negative count.
 	pub const fn new(user_data: T) -> Mutex<T> {
 		Mutex {
-			value: SpinlockIrqSave::new(true),
+			value: SpinlockIrqSave::new(None),
 			queue: SpinlockIrqSave::new(PriorityTaskQueue::new()),
 			data: UnsafeCell::new(user_data)
 		}
@@ -102,24 +110,51 @@ impl<T> Mutex<T> {
 	}
 }
 
-impl<T: ?Sized> Mutex<T>
-{
-	fn obtain_lock(&self) {
-		loop {
-			let mut count = self.value.lock();
+/// Block until `value` (a mutex's owner slot) is free, then claim it for
+/// the current task, applying priority inheritance to whichever task
+/// currently owns it while we wait. Shared by `Mutex::obtain_lock` and
+/// `Condvar::wait`'s re-acquire loop, which both need to run exactly this
+/// owner-match and priority-inheritance sequence. `obtain_lock_timeout`
+/// runs its own copy of the sequence (with a timed block and the
+/// timed-out-waiter cleanup), so a change to it has to be made there too.
+pub(crate) fn acquire(value: &SpinlockIrqSave<Option<TaskId>>, queue: &SpinlockIrqSave<PriorityTaskQueue>) {
+	loop {
+		let mut owner = value.lock();
 
-			if *count == true {
-				*count = false;
+		match *owner {
+			None => {
+				*owner = Some(get_current_taskid());
+				drop(owner);
+				unsafe {
+					note_lock_acquired(queue as *const _);
+					set_blocked_on_owner(None);
+				}
 				return;
-			} else {
-				self.queue.lock().push(get_current_priority(), &mut block_current_task());
+			},
+			Some(owner_tid) => {
+				let waiter_prio = get_current_priority();
+
+				// priority inheritance: a task holding a mutex we need
+				// must run at least at our own priority
+				unsafe {
+					raise_priority(owner_tid, waiter_prio);
+					set_blocked_on_owner(Some(owner_tid));
+					queue.lock().push(waiter_prio, &mut block_current_task());
+				}
 				// release lock
-				drop(count);
+				drop(owner);
 				// switch to the next task
-				reschedule();
+				unsafe { reschedule(); }
 			}
 		}
 	}
+}
+
+impl<T: ?Sized> Mutex<T>
+{
+	pub(crate) fn obtain_lock(&self) {
+		acquire(&self.value, &self.queue);
+	}
 
 	pub fn lock(&self) -> MutexGuard<T>
 	{
@@ -131,6 +166,65 @@ impl<T: ?Sized> Mutex<T>
 			data: unsafe { &mut *self.data.get() }
 		}
 	}
+
+	/// Like `obtain_lock`, but gives up after `ticks` timer ticks instead
+	/// of blocking forever. Returns `true` if the lock was obtained.
+	fn obtain_lock_timeout(&self, ticks: usize) -> bool {
+		loop {
+			let mut owner = self.value.lock();
+
+			match *owner {
+				None => {
+					*owner = Some(get_current_taskid());
+					drop(owner);
+					unsafe {
+						note_lock_acquired(&self.queue as *const _);
+						set_blocked_on_owner(None);
+					}
+					return true;
+				},
+				Some(owner_tid) => {
+					let waiter_prio = get_current_priority();
+
+					unsafe {
+						raise_priority(owner_tid, waiter_prio);
+						set_blocked_on_owner(Some(owner_tid));
+						self.queue.lock().push(waiter_prio, &mut block_current_task_timeout(ticks));
+					}
+					// release lock
+					drop(owner);
+					// switch to the next task
+					unsafe { reschedule(); }
+
+					if unsafe { current_task_timed_out() } {
+						// the timer woke us, not a `MutexGuard::drop` that
+						// popped us off `self.queue` itself => remove our
+						// own stale entry so a later `drop` doesn't hand
+						// the lock to a TCB we no longer occupy
+						self.queue.lock().remove(waiter_prio, get_current_taskid());
+						unsafe { set_blocked_on_owner(None); }
+						return false;
+					}
+				}
+			}
+		}
+	}
+
+	/// Like `lock`, but gives up after `ticks` timer ticks instead of
+	/// blocking forever.
+	pub fn lock_timeout(&self, ticks: usize) -> Result<MutexGuard<T>, TimedOut>
+	{
+		if self.obtain_lock_timeout(ticks) {
+			Ok(MutexGuard
+			{
+				value: &self.value,
+				queue: &self.queue,
+				data: unsafe { &mut *self.data.get() }
+			})
+		} else {
+			Err(TimedOut)
+		}
+	}
 }
 
 impl<T: ?Sized + Default> Default for Mutex<T> {
@@ -150,15 +244,30 @@ impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T>
 	fn deref_mut<'b>(&'b mut self) -> &'b mut T { &mut *self.data }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+	/// Splits the guard into its raw parts without running `Drop`, i.e.
+	/// without releasing the lock. Used by `Condvar::wait`, which has to
+	/// release the lock itself as part of a larger critical section.
+	pub(crate) fn into_raw_parts(self) -> (&'a SpinlockIrqSave<Option<TaskId>>, &'a SpinlockIrqSave<PriorityTaskQueue>, &'a mut T) {
+		let guard = mem::ManuallyDrop::new(self);
+		unsafe { (guard.value, guard.queue, ptr::read(&guard.data)) }
+	}
+}
+
 impl<'a, T: ?Sized> Drop for MutexGuard<'a, T>
 {
 	/// The dropping of the MutexGuard will release the lock it was created from.
 	fn drop(&mut self)
 	{
-		let mut count = self.value.lock();
-		*count = true;
+		let mut owner = self.value.lock();
+		*owner = None;
+
+		// priority inheritance: we may have been running above our own
+		// base priority to help a waiter along; drop back down to
+		// whatever is warranted by any locks we still hold
+		unsafe { note_lock_released(self.queue as *const _); }
 
-		// try to wakeup next task
+		// try to wakeup the highest-priority waiter
 		match self.queue.lock().pop() {
 			Some(task) => {
 				wakeup_task(task);
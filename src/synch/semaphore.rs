@@ -0,0 +1,100 @@
+// Copyright (c) 2017 Stefan Lankes, RWTH Aachen University
+//
+// MIT License
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use scheduler::task::*;
+use scheduler::{reschedule, block_current_task, get_current_priority, wakeup_task};
+use synch::spinlock::*;
+
+/// A counting semaphore, modeled on microITRON semaphore objects.
+///
+/// Unlike `Mutex`, a `Semaphore` carries a count of `count` available
+/// resources: `acquire` blocks while the count is not positive, `release`
+/// hands a resource back and wakes the highest-priority waiter. The count
+/// may be created negative, and `release` is free to push it past its
+/// initial value, since there is no notion of a maximum here.
+pub struct Semaphore {
+	/// number of resources currently available; tasks block while this
+	/// isn't positive
+	count: SpinlockIrqSave<isize>,
+	/// priority queue of tasks waiting for a resource
+	queue: SpinlockIrqSave<PriorityTaskQueue>
+}
+
+impl Semaphore {
+	/// Creates a new semaphore with the initial count specified.
+	///
+	/// The count specified can be thought of as a number of resources, and a
+	/// call to `acquire` will block until at least one resource is
+	/// available. It is valid to initialize a semaphore with a negative count.
+	pub const fn new(count: isize) -> Semaphore {
+		Semaphore {
+			count: SpinlockIrqSave::new(count),
+			queue: SpinlockIrqSave::new(PriorityTaskQueue::new())
+		}
+	}
+
+	/// Block the current task until a resource is available, then take it.
+	pub fn acquire(&self) {
+		loop {
+			let mut count = self.count.lock();
+
+			if *count > 0 {
+				*count -= 1;
+				return;
+			}
+
+			unsafe {
+				self.queue.lock().push(get_current_priority(), &mut block_current_task());
+			}
+			drop(count);
+			unsafe { reschedule(); }
+		}
+	}
+
+	/// Take a resource without blocking. Returns `true` if one was
+	/// available.
+	pub fn try_acquire(&self) -> bool {
+		let mut count = self.count.lock();
+
+		if *count > 0 {
+			*count -= 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Give a resource back and wake the highest-priority waiter, if any.
+	pub fn release(&self) {
+		let mut count = self.count.lock();
+		*count += 1;
+		drop(count);
+
+		if let Some(task) = self.queue.lock().pop() {
+			unsafe { wakeup_task(task); }
+		}
+	}
+}
+
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
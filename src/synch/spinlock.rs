@@ -0,0 +1,99 @@
+// Copyright (c) 2017 Stefan Lankes, RWTH Aachen University
+//
+// MIT License
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use core::cell::UnsafeCell;
+use core::ops::{Drop, Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use arch::irq::{irq_nested_disable, irq_nested_enable};
+
+/// A spinlock which also disables (nested) interrupts while the lock is held.
+///
+/// This is the only safe way for the kernel to protect data that is shared
+/// between a task and an interrupt handler: the interrupt flag is saved and
+/// cleared on `lock` and restored to its previous state once the returned
+/// guard is dropped.
+pub struct SpinlockIrqSave<T: ?Sized> {
+	locked: AtomicBool,
+	data: UnsafeCell<T>
+}
+
+/// A guard to which the protected data can be accessed
+///
+/// When the guard falls out of scope it will release the lock and restore
+/// the interrupt flag.
+pub struct SpinlockIrqSaveGuard<'a, T: ?Sized + 'a> {
+	locked: &'a AtomicBool,
+	flags: usize,
+	data: &'a mut T
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SpinlockIrqSave<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinlockIrqSave<T> {}
+
+impl<T> SpinlockIrqSave<T> {
+	pub const fn new(user_data: T) -> SpinlockIrqSave<T> {
+		SpinlockIrqSave {
+			locked: AtomicBool::new(false),
+			data: UnsafeCell::new(user_data)
+		}
+	}
+
+	pub fn into_inner(self) -> T {
+		let SpinlockIrqSave { data, .. } = self;
+		unsafe { data.into_inner() }
+	}
+}
+
+impl<T: ?Sized> SpinlockIrqSave<T> {
+	pub fn lock(&self) -> SpinlockIrqSaveGuard<T> {
+		let flags = irq_nested_disable();
+
+		while self.locked.compare_and_swap(false, true, Ordering::Acquire) != false {
+			// busy wait until we are able to acquire the lock
+		}
+
+		SpinlockIrqSaveGuard {
+			locked: &self.locked,
+			flags: flags,
+			data: unsafe { &mut *self.data.get() }
+		}
+	}
+}
+
+impl<'a, T: ?Sized> Deref for SpinlockIrqSaveGuard<'a, T> {
+	type Target = T;
+	fn deref<'b>(&'b self) -> &'b T { &*self.data }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinlockIrqSaveGuard<'a, T> {
+	fn deref_mut<'b>(&'b mut self) -> &'b mut T { &mut *self.data }
+}
+
+impl<'a, T: ?Sized> Drop for SpinlockIrqSaveGuard<'a, T> {
+	/// Releasing the lock also restores the interrupt flag that was in
+	/// effect before the lock was taken.
+	fn drop(&mut self) {
+		self.locked.store(false, Ordering::Release);
+		irq_nested_enable(self.flags);
+	}
+}